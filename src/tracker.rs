@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bitcoin::{BlockHash, Transaction, Txid};
 use bitcoin_slices::{
     bsl::{self, FindTransaction},
@@ -6,6 +6,13 @@ use bitcoin_slices::{
     Visit,
 };
 use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bitcoin::bip158::FilterHeader;
+use bitcoin::consensus::encode::serialize_hex;
 
 use crate::{
     cache::Cache,
@@ -20,12 +27,51 @@ use crate::{
     status::{Balance, ScriptHashStatus, UnspentEntry},
 };
 
+/// Time-to-live for cached fee estimates, in seconds.
+const FEE_ESTIMATE_TTL: Duration = Duration::from_secs(120);
+
+/// Bitcoin Core's default minimum relay feerate, in sat/vB.
+const DEFAULT_MIN_RELAY_FEE: f64 = 1.0;
+
+/// Per-target fee estimates derived from the mempool histogram, cached behind a short TTL so
+/// repeated subscriptions don't re-walk the histogram on every poll.
+#[derive(Default)]
+struct FeeEstimateCache {
+    targets: HashMap<u16, (Option<f64>, Instant)>,
+}
+
+/// Walk the histogram bins and return the feerate (in sat/vB) whose cumulative vsize fills
+/// `target_blocks` worth of block space. Bins are sorted from highest to lowest feerate here, so
+/// the result does not depend on the order the histogram happens to yield them. Feerates are
+/// `f32` (matching the Electrum histogram serialization) and widened to `f64`.
+fn fee_estimate_from_bins(
+    bins: impl IntoIterator<Item = (f32, u64)>,
+    target_blocks: u16,
+) -> Option<f64> {
+    // Bitcoin blocks hold up to 4M weight units, i.e. ~1M vbytes of transactions.
+    const BLOCK_VSIZE: u64 = 1_000_000;
+    let capacity = BLOCK_VSIZE.checked_mul(u64::from(target_blocks))?;
+    let mut bins: Vec<(f32, u64)> = bins.into_iter().collect();
+    bins.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    let mut cumulative = 0u64;
+    for (feerate, vsize) in bins {
+        cumulative = cumulative.saturating_add(vsize);
+        if cumulative >= capacity {
+            return Some(f64::from(feerate));
+        }
+    }
+    None
+}
+
 /// Electrum protocol subscriptions' tracker
 pub struct Tracker {
     index: Index,
     mempool: Mempool,
     metrics: Metrics,
     ignore_mempool: bool,
+    fee_estimates: Mutex<FeeEstimateCache>,
+    broadcast_cmd: Option<String>,
+    pub compact_filters: bool,
     pub silent_payments_index: bool,
 }
 
@@ -54,6 +100,9 @@ impl Tracker {
             mempool: Mempool::new(&metrics),
             metrics,
             ignore_mempool: config.ignore_mempool,
+            fee_estimates: Mutex::new(FeeEstimateCache::default()),
+            broadcast_cmd: config.broadcast_cmd.clone(),
+            compact_filters: config.compact_filters,
             silent_payments_index: config.silent_payments_index,
         })
     }
@@ -70,6 +119,30 @@ impl Tracker {
         &self.metrics
     }
 
+    /// Estimate the feerate (in sat/vB) needed for confirmation within `target_blocks`, walking
+    /// the mempool histogram until the cumulative vsize fills that many blocks' worth of space.
+    /// Results are cached per target behind [`FEE_ESTIMATE_TTL`] and invalidated on mempool sync.
+    pub(crate) fn fee_estimate(&self, target_blocks: u16) -> Option<f64> {
+        let mut cache = self.fee_estimates.lock().unwrap();
+        if let Some((fee, at)) = cache.targets.get(&target_blocks) {
+            if at.elapsed() < FEE_ESTIMATE_TTL {
+                return *fee;
+            }
+        }
+        let fee = self.compute_fee_estimate(target_blocks);
+        cache.targets.insert(target_blocks, (fee, Instant::now()));
+        fee
+    }
+
+    /// Minimum relay feerate (in sat/vB).
+    pub(crate) fn relay_fee(&self) -> f64 {
+        DEFAULT_MIN_RELAY_FEE
+    }
+
+    fn compute_fee_estimate(&self, target_blocks: u16) -> Option<f64> {
+        fee_estimate_from_bins(self.fees_histogram().bins(), target_blocks)
+    }
+
     pub(crate) fn get_unspent(&self, status: &ScriptHashStatus) -> Vec<UnspentEntry> {
         status.get_unspent(self.index.chain())
     }
@@ -79,9 +152,13 @@ impl Tracker {
         if done && self.silent_payments_index {
             done = self.index.silent_payments_sync(daemon, exit_flag)?;
         }
+        if done && self.compact_filters {
+            done = self.index.compact_filters_sync(daemon, exit_flag)?;
+        }
         if done && !self.ignore_mempool {
             self.mempool.sync(daemon, exit_flag);
             // TODO: double check tip - and retry on diff
+            *self.fee_estimates.lock().unwrap() = FeeEstimateCache::default();
         }
         Ok(done)
     }
@@ -136,6 +213,83 @@ impl Tracker {
         Ok(result)
     }
 
+    /// Broadcast a transaction to the network.
+    ///
+    /// When `broadcast_cmd` is configured, the raw transaction hex is handed to an external
+    /// process so operators can route submissions through Tor, a privacy relay, or a custom
+    /// endpoint. The hex is substituted for a `{tx_hex}` placeholder in the command; if no
+    /// placeholder is present it is piped to the process on stdin instead. Otherwise the bundled
+    /// daemon's RPC is used.
+    pub(crate) fn broadcast_transaction(
+        &self,
+        daemon: &Daemon,
+        tx: &Transaction,
+    ) -> Result<Txid> {
+        match &self.broadcast_cmd {
+            Some(cmd) => {
+                let tx_hex = serialize_hex(tx);
+                let pipe_stdin = !cmd.contains("{tx_hex}");
+                let rendered = cmd.replace("{tx_hex}", &tx_hex);
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(&rendered)
+                    .stdin(if pipe_stdin {
+                        Stdio::piped()
+                    } else {
+                        Stdio::null()
+                    })
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("failed to run broadcast command: {rendered}"))?;
+                if pipe_stdin {
+                    child
+                        .stdin
+                        .take()
+                        .expect("stdin was piped")
+                        .write_all(tx_hex.as_bytes())
+                        .context("failed to write tx hex to broadcast command")?;
+                }
+                let output = child
+                    .wait_with_output()
+                    .context("failed to wait for broadcast command")?;
+                if !output.status.success() {
+                    bail!(
+                        "broadcast command failed ({}): {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                Ok(tx.compute_txid())
+            }
+            None => daemon.broadcast(tx),
+        }
+    }
+
+    /// Raw BIP158 basic block filter for `hash`, when the compact-filter index is enabled.
+    pub(crate) fn get_block_filter(&self, hash: BlockHash) -> Option<Vec<u8>> {
+        if !self.compact_filters {
+            return None;
+        }
+        self.index.get_block_filter(hash)
+    }
+
+    /// BIP157 filter header for `hash`, when the compact-filter index is enabled.
+    pub(crate) fn get_filter_header(&self, hash: BlockHash) -> Option<FilterHeader> {
+        if !self.compact_filters {
+            return None;
+        }
+        self.index.get_filter_header(hash)
+    }
+
+    /// Up to `count` consecutive BIP157 filter headers starting at `start_height`.
+    pub(crate) fn get_filter_headers(&self, start_height: usize, count: usize) -> Vec<FilterHeader> {
+        if !self.compact_filters {
+            return Vec::new();
+        }
+        self.index.get_filter_headers(start_height, count)
+    }
+
     pub(crate) fn get_tweaks(&self, height: usize) -> Result<HashMap<u64, Vec<String>>> {
         let tweaks: Vec<(u64, Vec<String>)> = self.index.get_tweaks(height as u64).collect();
         let mut res: HashMap<u64, Vec<String>> = HashMap::new();
@@ -144,4 +298,52 @@ impl Tracker {
         }
         Ok(res)
     }
+
+    pub(crate) fn get_tweaks_range(
+        &self,
+        start_height: usize,
+        count: usize,
+    ) -> Result<HashMap<u64, Vec<String>>> {
+        // The tweak index is keyed by big-endian height, so `get_tweaks(start)` yields strictly
+        // ascending heights >= start; a single pass that stops at `start + count` therefore bounds
+        // the response and lets a light client fetch a whole sync window in one round trip.
+        let end = (start_height as u64).saturating_add(count as u64);
+        let mut res: HashMap<u64, Vec<String>> = HashMap::new();
+        for (height, tweaks) in self.index.get_tweaks(start_height as u64) {
+            if height >= end {
+                break;
+            }
+            res.entry(height).or_insert_with(Vec::new).extend(tweaks)
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fee_estimate_from_bins;
+
+    #[test]
+    fn fee_estimate_uses_confirmation_feerate_independent_of_bin_order() {
+        // High feerate fills 0.6 of a block, low feerate another 0.6 — a single block (1M vsize)
+        // is only filled once the low bin is included, so the confirmation feerate is the low one.
+        let high = (100.0f32, 600_000u64);
+        let low = (1.0f32, 600_000u64);
+        assert_eq!(fee_estimate_from_bins([high, low], 1), Some(1.0));
+        assert_eq!(fee_estimate_from_bins([low, high], 1), Some(1.0));
+    }
+
+    #[test]
+    fn fee_estimate_returns_top_feerate_when_it_fills_the_target() {
+        // The top bin alone exceeds a block's worth of space: bid at its feerate.
+        let top = (50.0f32, 1_200_000u64);
+        let rest = (1.0f32, 1_000_000u64);
+        assert_eq!(fee_estimate_from_bins([rest, top], 1), Some(50.0));
+    }
+
+    #[test]
+    fn fee_estimate_none_when_histogram_cannot_fill_target() {
+        let only = (20.0f32, 100_000u64);
+        assert_eq!(fee_estimate_from_bins([only], 1), None);
+    }
 }